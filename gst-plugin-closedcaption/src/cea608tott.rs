@@ -16,30 +16,159 @@ use gst::subclass::prelude::*;
 
 use super::cea608tott_ffi as ffi;
 use atomic_refcell::AtomicRefCell;
+use std::collections::VecDeque;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum Format {
     Srt,
     Vtt,
     Raw,
+    Ttml,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum InputFormat {
+    /// `closedcaption/x-cea-608, format=raw`: bare 608 byte pairs.
+    Cea608Raw,
+    /// `closedcaption/x-cea-608, format=s334-1a`: SMPTE 334-1 triples wrapping 608 pairs.
+    Cea608S334_1a,
+    /// `closedcaption/x-cea-708, format=cc_data`: bare `cc_data` triples.
+    Cea708CcData,
+    /// `closedcaption/x-cea-708, format=cdp`: SMPTE 334-2 CDP packets wrapping `cc_data`.
+    Cea708Cdp,
+}
+
+/// How many `DTVCC_PACKET_START`ed packets we'll track at once, so that a stream that never
+/// finishes one (dropped `DTVCC_PACKET_DATA` triples, a cut feed) can't grow this queue
+/// without bound; once it is full, the oldest is dropped to make room.
+const MAX_PENDING_DTVCC_PACKETS: usize = 2;
+
+/// A CEA-708 DTVCC packet being assembled from `DTVCC_PACKET_START`/`DTVCC_PACKET_DATA`
+/// `cc_data` triples.
+struct DtvccPacket {
+    sequence: u8,
+    expected_len: usize,
+    data: Vec<u8>,
+}
+
+/// One of the four logical CEA-608 data channels: CC1/CC2 are multiplexed on field 1, CC3/CC4
+/// on field 2, distinguished by the channel bit carried in each field's control codes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Channel {
+    CC1,
+    CC2,
+    CC3,
+    CC4,
+}
+
+impl Channel {
+    fn from_u32(value: u32) -> Self {
+        match value {
+            2 => Channel::CC2,
+            3 => Channel::CC3,
+            4 => Channel::CC4,
+            _ => Channel::CC1,
+        }
+    }
+
+    fn as_u32(self) -> u32 {
+        match self {
+            Channel::CC1 => 1,
+            Channel::CC2 => 2,
+            Channel::CC3 => 3,
+            Channel::CC4 => 4,
+        }
+    }
+
+    fn field(self) -> u8 {
+        match self {
+            Channel::CC1 | Channel::CC2 => 0,
+            Channel::CC3 | Channel::CC4 => 1,
+        }
+    }
+
+    fn channel_bit(self) -> bool {
+        matches!(self, Channel::CC2 | Channel::CC4)
+    }
+}
+
+impl Default for Channel {
+    fn default() -> Self {
+        Channel::CC1
+    }
+}
+
+/// The on-air caption mode signalled by CEA-608 miscellaneous control codes. Only `RollUp`
+/// gets special cue-merging treatment; the others emit one cue per `Status::Ready`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CaptionMode {
+    PopOn,
+    RollUp,
+    PaintOn,
+}
+
+impl Default for CaptionMode {
+    fn default() -> Self {
+        CaptionMode::PopOn
+    }
+}
+
+/// Text for one caption cue: plain for SRT/Raw, plus WebVTT markup and cue position.
+#[derive(Clone, Debug)]
+struct CaptionText {
+    plain: String,
+    vtt_text: String,
+    vtt_cue_position: Option<(u32, u8)>,
+}
+
+impl CaptionText {
+    fn plain(text: String) -> Self {
+        CaptionText {
+            plain: text.clone(),
+            vtt_text: text,
+            vtt_cue_position: None,
+        }
+    }
 }
 
 struct State {
     format: Option<Format>,
+    input_format: Option<InputFormat>,
     wrote_header: bool,
     caption_frame: CaptionFrame,
-    previous_text: Option<(gst::ClockTime, String)>,
+    previous_text: Option<(gst::ClockTime, CaptionText)>,
     index: u64,
+    dtvcc_service_number: u8,
+    dtvcc_packets: VecDeque<DtvccPacket>,
+    dtvcc_service_text: String,
+    channel: Channel,
+    field0_channel_bit: bool,
+    field1_channel_bit: bool,
+    caption_mode: CaptionMode,
+    merge_roll_up: bool,
+    roll_up_depth: usize,
+    roll_up_lines: VecDeque<String>,
 }
 
 impl Default for State {
     fn default() -> Self {
         State {
             format: None,
+            input_format: None,
             wrote_header: false,
             caption_frame: CaptionFrame::default(),
             previous_text: None,
             index: 1,
+            dtvcc_service_number: 1,
+            dtvcc_packets: VecDeque::new(),
+            dtvcc_service_text: String::new(),
+            channel: Channel::default(),
+            field0_channel_bit: false,
+            field1_channel_bit: false,
+            caption_mode: CaptionMode::default(),
+            merge_roll_up: true,
+            roll_up_depth: 2,
+            roll_up_lines: VecDeque::new(),
         }
     }
 }
@@ -59,6 +188,45 @@ lazy_static! {
     );
 }
 
+lazy_static! {
+    static ref PROPERTIES: [subclass::Property<'static>; 3] = [
+        subclass::Property("service-number", |name| {
+            glib::ParamSpec::uint(
+                name,
+                "Service Number",
+                "CEA-708 DTVCC service number to decode (1-63), only used for CEA-708 cc_data \
+                 input",
+                1,
+                63,
+                1,
+                glib::ParamFlags::READWRITE,
+            )
+        }),
+        subclass::Property("channel", |name| {
+            glib::ParamSpec::uint(
+                name,
+                "Channel",
+                "CEA-608 data channel to extract (1=CC1, 2=CC2, 3=CC3, 4=CC4), ignored for \
+                 CEA-708 cc_data input",
+                1,
+                4,
+                1,
+                glib::ParamFlags::READWRITE,
+            )
+        }),
+        subclass::Property("merge-roll-up", |name| {
+            glib::ParamSpec::boolean(
+                name,
+                "Merge Roll-Up",
+                "Coalesce consecutive roll-up cues that only scroll in a new bottom row into a \
+                 single growing cue, instead of emitting one cue per row change",
+                true,
+                glib::ParamFlags::READWRITE,
+            )
+        }),
+    ];
+}
+
 impl Cea608ToTt {
     fn sink_chain(
         &self,
@@ -84,85 +252,533 @@ impl Cea608ToTt {
         }
         let pts = (buffer_pts.unwrap() as f64) / 1_000_000_000.0;
 
+        let input_format = match state.input_format {
+            Some(input_format) => input_format,
+            None => {
+                gst_error!(CAT, obj: pad, "Not negotiated yet");
+                return Err(gst::FlowError::NotNegotiated);
+            }
+        };
+
         let data = buffer.map_readable().map_err(|_| {
             gst_error!(CAT, obj: pad, "Can't map buffer readable");
 
             gst::FlowError::Error
         })?;
 
-        if data.len() < 2 {
-            gst_error!(CAT, obj: pad, "Invalid closed caption packet size");
+        // A single buffer can carry several `cc_data` pairs (CDP, S334-1A), and each one can
+        // cross a cue boundary, so collect every cue flushed while decoding it rather than
+        // just the last.
+        let flushed_cues = match input_format {
+            InputFormat::Cea608Raw => {
+                if data.is_empty() || data.len() % 2 != 0 {
+                    gst_error!(CAT, obj: pad, "Invalid closed caption packet size");
 
-            return Ok(gst::FlowSuccess::Ok);
-        }
+                    return Ok(gst::FlowSuccess::Ok);
+                }
 
-        let previous_text = match state
-            .caption_frame
-            .decode((data[0] as u16) << 8 | data[1] as u16, pts)
-        {
-            Ok(Status::Ok) => return Ok(gst::FlowSuccess::Ok),
-            Err(_) => {
-                gst_error!(CAT, obj: pad, "Failed to decode closed caption packet");
-                return Ok(gst::FlowSuccess::Ok);
+                // Raw 608 carries no field marker of its own; assume field 1, as for a
+                // single-program stream with no field 2 data multiplexed in.
+                let pairs = data.chunks_exact(2).map(|pair| (0, pair[0], pair[1]));
+                self.decode_cea608_pairs(&mut state, pad, pairs, buffer_pts, pts)
             }
-            Ok(Status::Clear) => {
-                gst_debug!(CAT, obj: pad, "Clearing previous closed caption packet");
-                state.previous_text.take()
+            InputFormat::Cea608S334_1a => {
+                if data.is_empty() || data.len() % 3 != 0 {
+                    gst_error!(CAT, obj: pad, "Invalid S334-1A packet size");
+
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+
+                let pairs = Self::unwrap_s334_1a(&data);
+                self.decode_cea608_pairs(&mut state, pad, pairs.into_iter(), buffer_pts, pts)
             }
-            Ok(Status::Ready) => {
-                gst_debug!(CAT, obj: pad, "Have new closed caption packet");
-                let text = match state.caption_frame.to_text() {
-                    Ok(text) => text,
-                    Err(_) => {
-                        gst_error!(CAT, obj: pad, "Failed to convert caption frame to text");
+            InputFormat::Cea708Cdp => {
+                let pairs = match Self::unwrap_cdp(&data) {
+                    Some(pairs) => pairs,
+                    None => {
+                        gst_error!(CAT, obj: pad, "Invalid CDP packet");
+
                         return Ok(gst::FlowSuccess::Ok);
                     }
                 };
 
-                state.previous_text.replace((buffer_pts, text))
+                self.decode_cea608_pairs(&mut state, pad, pairs.into_iter(), buffer_pts, pts)
             }
-        };
+            InputFormat::Cea708CcData => {
+                if data.len() % 3 != 0 {
+                    gst_error!(CAT, obj: pad, "Invalid cc_data packet size");
 
-        let previous_text = match previous_text {
-            Some(previous_text) => previous_text,
-            None => {
-                gst_debug!(CAT, obj: pad, "Have no previous text");
-                return Ok(gst::FlowSuccess::Ok);
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+
+                for triple in data.chunks_exact(3) {
+                    Self::process_cc_data_triple(&mut state, triple[0], triple[1], triple[2]);
+                }
+
+                if state.dtvcc_service_text.is_empty() {
+                    return Ok(gst::FlowSuccess::Ok);
+                }
+
+                gst_debug!(CAT, obj: pad, "Have new closed caption packet");
+                let text = mem::take(&mut state.dtvcc_service_text);
+                match state
+                    .previous_text
+                    .replace((buffer_pts, CaptionText::plain(text)))
+                {
+                    Some(previous_text) => vec![previous_text],
+                    None => Vec::new(),
+                }
             }
         };
 
-        let duration = if buffer_pts > previous_text.0 {
-            buffer_pts - previous_text.0
-        } else {
-            0.into()
-        };
+        if flushed_cues.is_empty() {
+            gst_debug!(CAT, obj: pad, "Have no previous text");
+            return Ok(gst::FlowSuccess::Ok);
+        }
+
+        // Each cue's duration runs up to the timestamp of the next one flushed from this same
+        // buffer, or to the buffer's own pts for the last.
+        let next_timestamps: Vec<gst::ClockTime> =
+            flushed_cues.iter().skip(1).map(|(ts, _)| *ts).collect();
+
+        let mut out_buffers = Vec::with_capacity(flushed_cues.len());
+        for (i, (timestamp, text)) in flushed_cues.into_iter().enumerate() {
+            let end = next_timestamps.get(i).copied().unwrap_or(buffer_pts);
+            let duration = if end > timestamp {
+                end - timestamp
+            } else {
+                0.into()
+            };
+
+            let header_buffer = if !state.wrote_header {
+                state.wrote_header = true;
+
+                match format {
+                    Format::Vtt => Some(Self::create_vtt_header(timestamp)),
+                    Format::Ttml => Some(Self::create_ttml_header(timestamp)),
+                    Format::Srt | Format::Raw => None,
+                }
+            } else {
+                None
+            };
+
+            let buffer = match format {
+                Format::Vtt => Self::create_vtt_buffer(timestamp, duration, text),
+                Format::Srt => {
+                    Self::create_srt_buffer(timestamp, duration, state.index, text.plain)
+                }
+                Format::Raw => Self::create_raw_buffer(timestamp, duration, text.plain),
+                Format::Ttml => Self::create_ttml_buffer(timestamp, duration, text),
+            };
+            state.index += 1;
 
-        let (timestamp, text) = previous_text;
+            out_buffers.push((header_buffer, buffer));
+        }
+        drop(state);
 
-        let header_buffer = if !state.wrote_header {
-            state.wrote_header = true;
+        let last = out_buffers.len() - 1;
+        for (i, (header_buffer, buffer)) in out_buffers.into_iter().enumerate() {
+            if let Some(header_buffer) = header_buffer {
+                self.srcpad.push(header_buffer)?;
+            }
 
-            match format {
-                Format::Vtt => Some(Self::create_vtt_header(timestamp)),
-                Format::Srt | Format::Raw => None,
+            if i == last {
+                return self.srcpad.push(buffer);
             }
+
+            self.srcpad.push(buffer)?;
+        }
+
+        unreachable!()
+    }
+
+    /// Is `b0` (ignoring the parity bit) in the 0x10-0x1f control code range?
+    fn is_control_code(b0: u8) -> bool {
+        (b0 & 0x7f) >= 0x10 && (b0 & 0x7f) <= 0x1f
+    }
+
+    fn control_code_channel_bit(b0: u8) -> bool {
+        (b0 & 0x7f) & 0x08 != 0
+    }
+
+    /// `b0` with the channel-select bit (0x08) masked off, since channel 1 and 2 share commands.
+    fn control_code_base(b0: u8) -> u8 {
+        (b0 & 0x7f) & !0x08
+    }
+
+    /// The caption mode implied by `(b0, b1)`, if it's `RCL`/`RU2`/`RU3`/`RU4`/`RDC`.
+    fn caption_mode_for_control_code(b0: u8, b1: u8) -> Option<CaptionMode> {
+        // Miscellaneous control codes are 0x14 on field 1 and 0x15 on field 2 (CC3/CC4).
+        if !matches!(Self::control_code_base(b0), 0x14 | 0x15) {
+            return None;
+        }
+
+        match b1 & 0x7f {
+            0x20 => Some(CaptionMode::PopOn),   // RCL: Resume Caption Loading
+            0x25 | 0x26 | 0x27 => Some(CaptionMode::RollUp), // RU2/RU3/RU4
+            0x29 => Some(CaptionMode::PaintOn), // RDC: Resume Direct Captioning
+            _ => None,
+        }
+    }
+
+    /// The roll-up depth (number of visible rows) implied by an `RU2`/`RU3`/`RU4` control code.
+    fn roll_up_depth_for_control_code(b1: u8) -> usize {
+        match b1 & 0x7f {
+            0x26 => 3,
+            0x27 => 4,
+            _ => 2,
+        }
+    }
+
+    /// Is `new_lines` just `old_lines` with one more row scrolled in at the bottom?
+    fn is_roll_up_continuation(old_lines: &VecDeque<String>, new_lines: &[&str]) -> bool {
+        if old_lines.is_empty() {
+            return new_lines.len() <= 1;
+        }
+
+        if new_lines.is_empty() || new_lines.len() > old_lines.len() + 1 {
+            return false;
+        }
+
+        let skip = (old_lines.len() + 1).saturating_sub(new_lines.len());
+        old_lines
+            .iter()
+            .skip(skip)
+            .map(|s| s.as_str())
+            .eq(new_lines[..new_lines.len() - 1].iter().copied())
+    }
+
+    /// Decodes a sequence of CEA-608 byte pairs tagged with the field (0 or 1) they arrived
+    /// on, dropping pairs that belong to a different channel than `state.channel` and tracking
+    /// the currently selected channel per field from control code bytes as they go by. Returns,
+    /// in order, every cue flushed by a `Ready`/`Clear`/mode-change transition seen while
+    /// decoding `pairs` — a single buffer can carry several cue boundaries (CDP and S334-1A
+    /// routinely batch more than one pair), so all of them must be pushed downstream, not just
+    /// the last.
+    fn decode_cea608_pairs(
+        &self,
+        state: &mut State,
+        pad: &gst::Pad,
+        pairs: impl Iterator<Item = (u8, u8, u8)>,
+        buffer_pts: gst::ClockTime,
+        pts: f64,
+    ) -> Vec<(gst::ClockTime, CaptionText)> {
+        let mut flushed = Vec::new();
+
+        for (field, b0, b1) in pairs {
+            if Self::is_control_code(b0) {
+                let bit = Self::control_code_channel_bit(b0);
+                if field == 0 {
+                    state.field0_channel_bit = bit;
+                } else {
+                    state.field1_channel_bit = bit;
+                }
+            }
+
+            let current_bit = if field == 0 {
+                state.field0_channel_bit
+            } else {
+                state.field1_channel_bit
+            };
+
+            if field != state.channel.field() || current_bit != state.channel.channel_bit() {
+                continue;
+            }
+
+            if let Some(mode) = Self::caption_mode_for_control_code(b0, b1) {
+                if mode != state.caption_mode {
+                    state.roll_up_lines.clear();
+                    // The pending cue belongs to the mode we're leaving: flush it now rather
+                    // than risk a later roll-up Ready mistaking it for a continuation and
+                    // silently overwriting its text while keeping its stale start time.
+                    if let Some(pending) = state.previous_text.take() {
+                        flushed.push(pending);
+                    }
+                }
+                state.caption_mode = mode;
+                if mode == CaptionMode::RollUp {
+                    state.roll_up_depth = Self::roll_up_depth_for_control_code(b1);
+                }
+            }
+
+            match state
+                .caption_frame
+                .decode((b0 as u16) << 8 | b1 as u16, pts)
+            {
+                Ok(Status::Ok) => continue,
+                Err(_) => {
+                    gst_error!(CAT, obj: pad, "Failed to decode closed caption packet");
+                    continue;
+                }
+                Ok(Status::Clear) => {
+                    gst_debug!(CAT, obj: pad, "Clearing previous closed caption packet");
+                    state.roll_up_lines.clear();
+                    if let Some(pending) = state.previous_text.take() {
+                        flushed.push(pending);
+                    }
+                }
+                Ok(Status::Ready) => {
+                    gst_debug!(CAT, obj: pad, "Have new closed caption packet");
+                    let plain = match state.caption_frame.to_text() {
+                        Ok(text) => text,
+                        Err(_) => {
+                            gst_error!(CAT, obj: pad, "Failed to convert caption frame to text");
+                            continue;
+                        }
+                    };
+                    let (vtt_text, vtt_cue_position) = match state.caption_frame.to_vtt_cue() {
+                        Ok(cue) => cue,
+                        Err(_) => (plain.clone(), None),
+                    };
+
+                    let text = CaptionText {
+                        plain,
+                        vtt_text,
+                        vtt_cue_position,
+                    };
+
+                    if state.merge_roll_up && state.caption_mode == CaptionMode::RollUp {
+                        let lines: Vec<&str> = text.plain.lines().collect();
+                        let continuation =
+                            Self::is_roll_up_continuation(&state.roll_up_lines, &lines);
+
+                        state.roll_up_lines = lines.into_iter().map(String::from).collect();
+                        while state.roll_up_lines.len() > state.roll_up_depth {
+                            state.roll_up_lines.pop_front();
+                        }
+
+                        if continuation && state.previous_text.is_some() {
+                            state.previous_text.as_mut().unwrap().1 = text;
+                        } else if let Some(pending) =
+                            state.previous_text.replace((buffer_pts, text))
+                        {
+                            flushed.push(pending);
+                        }
+                    } else if let Some(pending) = state.previous_text.replace((buffer_pts, text))
+                    {
+                        flushed.push(pending);
+                    }
+                }
+            }
+        }
+
+        flushed
+    }
+
+    /// Unwraps a `s334-1a` buffer into its `(field, cc byte 1, cc byte 2)` triples; bit 7 of
+    /// the field selector is set for field 1 and clear for field 2.
+    fn unwrap_s334_1a(data: &[u8]) -> Vec<(u8, u8, u8)> {
+        data.chunks_exact(3)
+            .map(|triple| {
+                let field = if triple[0] & 0x80 != 0 { 0 } else { 1 };
+                (field, triple[1], triple[2])
+            })
+            .collect()
+    }
+
+    /// Unwraps a CDP packet's `cc_data` section into CEA-608 pairs (`cc_type` 0/1, which map
+    /// directly to field 0/1); DTVCC `cc_type` 2/3 entries are skipped.
+    fn unwrap_cdp(data: &[u8]) -> Option<Vec<(u8, u8, u8)>> {
+        if data.len() < 7 || data[0] != 0x96 || data[1] != 0x69 {
+            return None;
+        }
+
+        // Skip the CDP header (identifier, length, framerate/flags) and the
+        // cdp_hdr_sequence_counter to reach the first section.
+        let mut pos = 7;
+
+        // cdp_flags (byte 4) bit 0x10 is time_code_present: a fixed 5-byte time_code_section
+        // (1 byte id + 4 byte payload) sits ahead of cc_data_section. Skip it by its known
+        // length rather than scanning for the cc_data id, which could otherwise match a byte
+        // inside the time code payload.
+        if data[4] & 0x10 != 0 {
+            if pos + 5 > data.len() {
+                return None;
+            }
+            pos += 5;
+        }
+
+        let mut pairs = Vec::new();
+
+        if pos + 1 < data.len() && data[pos] == 0x72 {
+            // cc_data section
+            let cc_count = (data[pos + 1] & 0x1f) as usize;
+            pos += 2;
+
+            for _ in 0..cc_count {
+                if pos + 2 >= data.len() {
+                    break;
+                }
+
+                let marker_byte = data[pos];
+                let cc_valid = (marker_byte & 0x04) != 0;
+                let cc_type = marker_byte & 0x03;
+
+                if cc_valid && (cc_type == 0b00 || cc_type == 0b01) {
+                    pairs.push((cc_type, data[pos + 1], data[pos + 2]));
+                }
+
+                pos += 3;
+            }
+        }
+
+        Some(pairs)
+    }
+
+    /// Feeds one `cc_data` triple into the DTVCC packet reassembly state.
+    fn process_cc_data_triple(state: &mut State, marker_byte: u8, b0: u8, b1: u8) {
+        let cc_valid = (marker_byte & 0x04) != 0;
+        let cc_type = marker_byte & 0x03;
+
+        if !cc_valid {
+            return;
+        }
+
+        match cc_type {
+            // DTVCC_PACKET_START
+            0b11 => {
+                while state.dtvcc_packets.len() >= MAX_PENDING_DTVCC_PACKETS {
+                    let dropped = state.dtvcc_packets.pop_front().unwrap();
+                    gst_warning!(
+                        CAT,
+                        "Dropping incomplete DTVCC packet (sequence {}, {} of {} bytes)",
+                        dropped.sequence,
+                        dropped.data.len(),
+                        dropped.expected_len
+                    );
+                }
+
+                let sequence = (b0 & 0xc0) >> 6;
+                let expected_len = Self::dtvcc_packet_len(b0);
+                state.dtvcc_packets.push_back(DtvccPacket {
+                    sequence,
+                    expected_len,
+                    data: vec![b0, b1],
+                });
+            }
+            // DTVCC_PACKET_DATA: continues whichever in-flight packet hasn't reached its
+            // expected length yet.
+            0b10 => {
+                if let Some(packet) = state
+                    .dtvcc_packets
+                    .iter_mut()
+                    .rev()
+                    .find(|packet| packet.data.len() < packet.expected_len)
+                {
+                    packet.data.push(b0);
+                    packet.data.push(b1);
+                }
+            }
+            // CEA-608 field pairs, not our concern on this path
+            _ => return,
+        }
+
+        while state
+            .dtvcc_packets
+            .front()
+            .map_or(false, |packet| packet.data.len() >= packet.expected_len)
+        {
+            let packet = state.dtvcc_packets.pop_front().unwrap();
+            Self::parse_dtvcc_packet(state, &packet.data);
+        }
+    }
+
+    /// `packet_size_code` (the header's low 6 bits) is 0 for a 128 byte packet, otherwise the
+    /// packet is `packet_size_code * 2` bytes long.
+    fn dtvcc_packet_len(header: u8) -> usize {
+        let packet_size_code = (header & 0x3f) as usize;
+        if packet_size_code == 0 {
+            128
         } else {
-            None
-        };
+            packet_size_code * 2
+        }
+    }
 
-        let buffer = match format {
-            Format::Vtt => Self::create_vtt_buffer(timestamp, duration, text),
-            Format::Srt => Self::create_srt_buffer(timestamp, duration, state.index, text),
-            Format::Raw => Self::create_raw_buffer(timestamp, duration, text),
-        };
-        state.index += 1;
-        drop(state);
+    /// Walks the service blocks of a reassembled DTVCC packet, decoding the one matching
+    /// `dtvcc_service_number`.
+    fn parse_dtvcc_packet(state: &mut State, data: &[u8]) {
+        let mut pos = 1;
+
+        while pos < data.len() {
+            let service_header = data[pos];
+            let mut service_number = (service_header & 0xe0) >> 5;
+            let block_size = (service_header & 0x1f) as usize;
+            pos += 1;
+
+            // A 3-bit service_number of 7 means "extended": the real 1-63 service number is
+            // the top 6 bits of the following byte, read before the block payload.
+            if service_number == 7 {
+                if pos >= data.len() {
+                    break;
+                }
+                service_number = (data[pos] & 0xfc) >> 2;
+                pos += 1;
+            }
+
+            if block_size == 0 || pos + block_size > data.len() {
+                break;
+            }
+
+            if service_number == state.dtvcc_service_number {
+                Self::decode_service_block(state, &data[pos..pos + block_size]);
+            }
+
+            pos += block_size;
+        }
+    }
 
-        if let Some(header_buffer) = header_buffer {
-            self.srcpad.push(header_buffer)?;
+    /// The number of bytes following a C1 (0x80-0x9F) command code, per CEA-708-E table 6-4:
+    /// window selection/reset codes take none, the pen/window attribute codes take a fixed
+    /// number matching their payload, and `DFx` (window definition) takes 6.
+    fn c1_param_len(code: u8) -> usize {
+        match code {
+            0x80..=0x87 => 0, // CW0-CW7: SetCurrentWindow
+            0x88..=0x8d => 1, // CLW, DSW, HDW, TGW, DLW, DLY: window-bitmap/delay param
+            0x8e | 0x8f => 0, // DLC, RST
+            0x90 => 2,        // SPA: SetPenAttributes
+            0x91 => 3,        // SPC: SetPenColor
+            0x92 => 2,        // SPL: SetPenLocation
+            0x93..=0x96 => 0, // reserved
+            0x97 => 4,        // SWA: SetWindowAttributes
+            0x98..=0x9f => 6, // DFx: DefineWindow0-7
+            _ => 0,
         }
+    }
 
-        self.srcpad.push(buffer)
+    /// Accumulates the visible text of a single service block into `dtvcc_service_text`,
+    /// skipping over C0 (0x00-0x1F) control codes other than CR, which becomes a newline, and
+    /// C1 (0x80-0x9F) window/pen commands.
+    fn decode_service_block(state: &mut State, block: &[u8]) {
+        let mut i = 0;
+
+        while i < block.len() {
+            let code = block[i];
+            match code {
+                0x00 => i += 1,
+                // CR: roll to a new line of the current window
+                0x0d => {
+                    state.dtvcc_service_text.push('\n');
+                    i += 1;
+                }
+                // EXT1: extended code group prefix, consumes the following code byte too.
+                0x10 => i += 2.min(block.len() - i),
+                // Remaining C0 control codes take no extra bytes.
+                0x01..=0x0f | 0x11..=0x1f => i += 1,
+                // C1: window/pen commands, each with its own fixed parameter length.
+                0x80..=0x9f => {
+                    let param_len = Self::c1_param_len(code);
+                    if i + 1 + param_len > block.len() {
+                        break;
+                    }
+                    i += 1 + param_len;
+                }
+                // G0/G1 printable codes
+                0x20..=0x7f | 0xa0..=0xff => {
+                    state.dtvcc_service_text.push(code as char);
+                    i += 1;
+                }
+            }
+        }
     }
 
     fn create_vtt_header(timestamp: gst::ClockTime) -> gst::Buffer {
@@ -197,7 +813,7 @@ impl Cea608ToTt {
     fn create_vtt_buffer(
         timestamp: gst::ClockTime,
         duration: gst::ClockTime,
-        text: String,
+        text: CaptionText,
     ) -> gst::Buffer {
         use std::fmt::Write;
 
@@ -206,13 +822,17 @@ impl Cea608ToTt {
         let (h1, m1, s1, ms1) = Self::split_time(timestamp);
         let (h2, m2, s2, ms2) = Self::split_time(timestamp + duration);
 
-        writeln!(
+        write!(
             &mut data,
-            "{:02}:{:02}:{:02}.{:03} --> {:02}:{:02}:{:02}.{:03}\r",
+            "{:02}:{:02}:{:02}.{:03} --> {:02}:{:02}:{:02}.{:03}",
             h1, m1, s1, ms1, h2, m2, s2, ms2
         )
         .unwrap();
-        writeln!(&mut data, "{}\r", text).unwrap();
+        if let Some((line, position)) = text.vtt_cue_position {
+            write!(&mut data, " line:{} position:{}%", line, position).unwrap();
+        }
+        writeln!(&mut data, "\r").unwrap();
+        writeln!(&mut data, "{}\r", text.vtt_text).unwrap();
         writeln!(&mut data, "\r").unwrap();
 
         let mut buffer = gst::Buffer::from_mut_slice(data.into_bytes());
@@ -273,14 +893,117 @@ impl Cea608ToTt {
         buffer
     }
 
+    /// Picks the IMSC region for a cue: the top half of the grid goes to `top`, everything
+    /// else (including unpositioned cues) to `bottom`.
+    fn ttml_region(cue_position: Option<(u32, u8)>) -> &'static str {
+        match cue_position {
+            Some((row, _)) if (row as usize) < ffi::CAPTION_FRAME_ROWS as usize / 2 => "top",
+            _ => "bottom",
+        }
+    }
+
+    fn xml_escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+
+    fn create_ttml_header(timestamp: gst::ClockTime) -> gst::Buffer {
+        use std::fmt::Write;
+
+        let mut data = String::new();
+        writeln!(&mut data, r#"<?xml version="1.0" encoding="utf-8"?>"#).unwrap();
+        writeln!(
+            &mut data,
+            r#"<tt xmlns="http://www.w3.org/ns/ttml" xmlns:tts="http://www.w3.org/ns/ttml#styling">"#
+        )
+        .unwrap();
+        writeln!(&mut data, "  <head>").unwrap();
+        writeln!(
+            &mut data,
+            r#"    <region xml:id="top" tts:origin="10% 10%" tts:extent="80% 20%" tts:displayAlign="before"/>"#
+        )
+        .unwrap();
+        writeln!(
+            &mut data,
+            r#"    <region xml:id="bottom" tts:origin="10% 70%" tts:extent="80% 20%" tts:displayAlign="after"/>"#
+        )
+        .unwrap();
+        writeln!(&mut data, "  </head>").unwrap();
+        writeln!(&mut data, "  <body>").unwrap();
+        writeln!(&mut data, "    <div>").unwrap();
+
+        let mut buffer = gst::Buffer::from_mut_slice(data.into_bytes());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(timestamp);
+        }
+
+        buffer
+    }
+
+    fn create_ttml_buffer(
+        timestamp: gst::ClockTime,
+        duration: gst::ClockTime,
+        text: CaptionText,
+    ) -> gst::Buffer {
+        use std::fmt::Write;
+
+        let mut data = String::new();
+
+        let (h1, m1, s1, ms1) = Self::split_time(timestamp);
+        let (h2, m2, s2, ms2) = Self::split_time(timestamp + duration);
+        let region = Self::ttml_region(text.vtt_cue_position);
+        let escaped = Self::xml_escape(&text.plain).replace('\n', "<br/>");
+
+        writeln!(
+            &mut data,
+            r#"      <p begin="{:02}:{:02}:{:02}.{:03}" end="{:02}:{:02}:{:02}.{:03}" region="{}">{}</p>"#,
+            h1, m1, s1, ms1, h2, m2, s2, ms2, region, escaped
+        )
+        .unwrap();
+
+        let mut buffer = gst::Buffer::from_mut_slice(data.into_bytes());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(timestamp);
+            buffer.set_duration(duration);
+        }
+
+        buffer
+    }
+
+    /// Closes the `<div>`/`<body>`/`<tt>` elements opened by `create_ttml_header`. Pushed once,
+    /// on EOS, after the final cue (if any).
+    fn create_ttml_footer() -> gst::Buffer {
+        use std::fmt::Write;
+
+        let mut data = String::new();
+        writeln!(&mut data, "    </div>").unwrap();
+        writeln!(&mut data, "  </body>").unwrap();
+        writeln!(&mut data, "</tt>").unwrap();
+
+        gst::Buffer::from_mut_slice(data.into_bytes())
+    }
+
     fn sink_event(&self, pad: &gst::Pad, element: &gst::Element, event: gst::Event) -> bool {
         use gst::EventView;
 
         gst_log!(CAT, obj: pad, "Handling event {:?}", event);
         match event.view() {
-            EventView::Caps(..) => {
+            EventView::Caps(ev) => {
                 let mut state = self.state.borrow_mut();
 
+                let sink_caps = ev.get_caps();
+                let sink_s = sink_caps.get_structure(0).unwrap();
+                let sink_format = sink_s.get::<&str>("format").ok().flatten();
+                state.input_format = Some(match (sink_s.get_name(), sink_format) {
+                    ("closedcaption/x-cea-608", Some("s334-1a")) => InputFormat::Cea608S334_1a,
+                    ("closedcaption/x-cea-708", Some("cdp")) => InputFormat::Cea708Cdp,
+                    ("closedcaption/x-cea-708", _) => InputFormat::Cea708CcData,
+                    _ => InputFormat::Cea608Raw,
+                });
+
                 if state.format.is_some() {
                     return true;
                 }
@@ -316,6 +1039,9 @@ impl Cea608ToTt {
                     gst::Caps::builder("text/x-raw")
                         .field("format", &"utf8")
                         .build()
+                } else if s.get_name() == "application/ttml+xml" {
+                    state.format = Some(Format::Ttml);
+                    gst::Caps::builder("application/ttml+xml").build()
                 } else {
                     unreachable!();
                 };
@@ -328,19 +1054,28 @@ impl Cea608ToTt {
                 let mut state = self.state.borrow_mut();
                 state.caption_frame = CaptionFrame::default();
                 state.previous_text = None;
+                state.dtvcc_packets.clear();
+                state.dtvcc_service_text.clear();
+                state.field0_channel_bit = false;
+                state.field1_channel_bit = false;
+                state.caption_mode = CaptionMode::default();
+                state.roll_up_lines.clear();
             }
             EventView::Eos(..) => {
                 let mut state = self.state.borrow_mut();
-                if let Some((timestamp, text)) = state.previous_text.take() {
+                let format = state.format;
+
+                let final_buffers = state.previous_text.take().map(|(timestamp, text)| {
                     gst_debug!(CAT, obj: pad, "Outputting final text on EOS");
 
-                    let format = state.format.unwrap();
+                    let format = format.unwrap();
 
                     let header_buffer = if !state.wrote_header {
                         state.wrote_header = true;
 
                         match format {
                             Format::Vtt => Some(Self::create_vtt_header(timestamp)),
+                            Format::Ttml => Some(Self::create_ttml_header(timestamp)),
                             Format::Srt | Format::Raw => None,
                         }
                     } else {
@@ -350,19 +1085,34 @@ impl Cea608ToTt {
                     let buffer = match format {
                         Format::Vtt => Self::create_vtt_buffer(timestamp, 0.into(), text),
                         Format::Srt => {
-                            Self::create_srt_buffer(timestamp, 0.into(), state.index, text)
+                            Self::create_srt_buffer(timestamp, 0.into(), state.index, text.plain)
                         }
-                        Format::Raw => Self::create_raw_buffer(timestamp, 0.into(), text),
+                        Format::Raw => Self::create_raw_buffer(timestamp, 0.into(), text.plain),
+                        Format::Ttml => Self::create_ttml_buffer(timestamp, 0.into(), text),
                     };
                     state.index += 1;
-                    drop(state);
 
+                    (header_buffer, buffer)
+                });
+
+                let footer_buffer = if format == Some(Format::Ttml) && state.wrote_header {
+                    Some(Self::create_ttml_footer())
+                } else {
+                    None
+                };
+                drop(state);
+
+                if let Some((header_buffer, buffer)) = final_buffers {
                     if let Some(header_buffer) = header_buffer {
                         let _ = self.srcpad.push(header_buffer);
                     }
 
                     let _ = self.srcpad.push(buffer);
                 }
+
+                if let Some(footer_buffer) = footer_buffer {
+                    let _ = self.srcpad.push(footer_buffer);
+                }
             }
             _ => (),
         }
@@ -414,7 +1164,7 @@ impl ObjectSubclass for Cea608ToTt {
         klass.set_metadata(
             "CEA-608 to TT",
             "Generic",
-            "Converts CEA-608 Closed Captions to SRT/VTT timed text",
+            "Converts CEA-608/CEA-708 Closed Captions to SRT/VTT/TTML timed text",
             "Sebastian Dröge <sebastian@centricular.com>",
         );
 
@@ -435,6 +1185,10 @@ impl ObjectSubclass for Cea608ToTt {
                 .field("format", &"utf8")
                 .build();
             caps.append_structure(s);
+
+            // TTML / IMSC
+            let s = gst::Structure::builder("application/ttml+xml").build();
+            caps.append_structure(s);
         }
 
         let src_pad_template = gst::PadTemplate::new(
@@ -446,9 +1200,30 @@ impl ObjectSubclass for Cea608ToTt {
         .unwrap();
         klass.add_pad_template(src_pad_template);
 
-        let caps = gst::Caps::builder("closedcaption/x-cea-608")
-            .field("format", &"raw")
-            .build();
+        let mut caps = gst::Caps::new_empty();
+        {
+            let caps = caps.get_mut().unwrap();
+
+            let s = gst::Structure::builder("closedcaption/x-cea-608")
+                .field("format", &"raw")
+                .build();
+            caps.append_structure(s);
+
+            let s = gst::Structure::builder("closedcaption/x-cea-608")
+                .field("format", &"s334-1a")
+                .build();
+            caps.append_structure(s);
+
+            let s = gst::Structure::builder("closedcaption/x-cea-708")
+                .field("format", &"cc_data")
+                .build();
+            caps.append_structure(s);
+
+            let s = gst::Structure::builder("closedcaption/x-cea-708")
+                .field("format", &"cdp")
+                .build();
+            caps.append_structure(s);
+        }
 
         let sink_pad_template = gst::PadTemplate::new(
             "sink",
@@ -458,6 +1233,8 @@ impl ObjectSubclass for Cea608ToTt {
         )
         .unwrap();
         klass.add_pad_template(sink_pad_template);
+
+        klass.install_properties(&*PROPERTIES);
     }
 }
 
@@ -471,6 +1248,48 @@ impl ObjectImpl for Cea608ToTt {
         element.add_pad(&self.sinkpad).unwrap();
         element.add_pad(&self.srcpad).unwrap();
     }
+
+    fn set_property(&self, _obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+
+        match *prop {
+            subclass::Property("service-number", ..) => {
+                let mut state = self.state.borrow_mut();
+                let service_number: u32 = value.get_some().expect("type checked upstream");
+                state.dtvcc_service_number = service_number as u8;
+            }
+            subclass::Property("channel", ..) => {
+                let mut state = self.state.borrow_mut();
+                let channel: u32 = value.get_some().expect("type checked upstream");
+                state.channel = Channel::from_u32(channel);
+            }
+            subclass::Property("merge-roll-up", ..) => {
+                let mut state = self.state.borrow_mut();
+                state.merge_roll_up = value.get_some().expect("type checked upstream");
+            }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &glib::Object, id: usize) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id];
+
+        match *prop {
+            subclass::Property("service-number", ..) => {
+                let state = self.state.borrow();
+                Ok((state.dtvcc_service_number as u32).to_value())
+            }
+            subclass::Property("channel", ..) => {
+                let state = self.state.borrow();
+                Ok(state.channel.as_u32().to_value())
+            }
+            subclass::Property("merge-roll-up", ..) => {
+                let state = self.state.borrow();
+                Ok(state.merge_roll_up.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
 }
 
 impl ElementImpl for Cea608ToTt {
@@ -561,10 +1380,287 @@ impl CaptionFrame {
             String::from_utf8(data).map_err(|_| Error)
         }
     }
+
+    /// Groups each non-empty row of the grid into same-style runs, with its start column.
+    fn styled_rows(&self) -> Vec<(usize, usize, Vec<StyledRun>)> {
+        let mut rows = Vec::new();
+
+        for row in 0..ffi::CAPTION_FRAME_ROWS as usize {
+            let frame_row = unsafe { &self.0.rows[row] };
+            let length = (frame_row.length as usize).min(ffi::CAPTION_FRAME_COLUMNS as usize);
+
+            let mut start_column = None;
+            let mut runs: Vec<StyledRun> = Vec::new();
+
+            for column in 0..length {
+                let cell = unsafe { &frame_row.characters[column] };
+                let ch = cell.symbol[0] as char;
+                if ch == '\0' {
+                    continue;
+                }
+
+                if start_column.is_none() {
+                    start_column = Some(column);
+                }
+
+                let style = CellStyle {
+                    color: CellColor::from_ffi(cell.style()),
+                    underline: cell.underline() != 0,
+                };
+
+                match runs.last_mut() {
+                    Some(run) if run.style == style => run.text.push(ch),
+                    _ => runs.push(StyledRun {
+                        style,
+                        text: ch.to_string(),
+                    }),
+                }
+            }
+
+            if let Some(start_column) = start_column {
+                rows.push((row, start_column, runs));
+            }
+        }
+
+        rows
+    }
+
+    /// Builds WebVTT cue text with inline style tags and a cue position, falling back to
+    /// plain text when the frame carries no positioning.
+    fn to_vtt_cue(&self) -> Result<(String, Option<(u32, u8)>), Error> {
+        let rows = self.styled_rows();
+
+        if rows.is_empty() {
+            return self.to_text().map(|text| (text, None));
+        }
+
+        let cue_position = rows.first().map(|(row, column, _)| {
+            (
+                *row as u32,
+                ((*column * 100) / ffi::CAPTION_FRAME_COLUMNS as usize) as u8,
+            )
+        });
+
+        let mut text = String::new();
+        for (i, (_, _, runs)) in rows.iter().enumerate() {
+            if i > 0 {
+                text.push('\n');
+            }
+
+            for run in runs {
+                text.push_str(&run.style.wrap(&run.text));
+            }
+        }
+
+        Ok((text, cue_position))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CellColor {
+    White,
+    Green,
+    Blue,
+    Cyan,
+    Red,
+    Yellow,
+    Magenta,
+    Italics,
+}
+
+impl CellColor {
+    fn from_ffi(style: ffi::eia608_style_t) -> Self {
+        match style {
+            ffi::eia608_style_t_eia608_style_green => CellColor::Green,
+            ffi::eia608_style_t_eia608_style_blue => CellColor::Blue,
+            ffi::eia608_style_t_eia608_style_cyan => CellColor::Cyan,
+            ffi::eia608_style_t_eia608_style_red => CellColor::Red,
+            ffi::eia608_style_t_eia608_style_yellow => CellColor::Yellow,
+            ffi::eia608_style_t_eia608_style_magenta => CellColor::Magenta,
+            ffi::eia608_style_t_eia608_style_italics => CellColor::Italics,
+            _ => CellColor::White,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct CellStyle {
+    color: CellColor,
+    underline: bool,
+}
+
+impl CellStyle {
+    /// Wraps `text` in the inline tags implied by this style (only used for WebVTT output).
+    fn wrap(&self, text: &str) -> String {
+        let mut out = text.to_string();
+
+        if self.underline {
+            out = format!("<u>{}</u>", out);
+        }
+
+        out = match self.color {
+            CellColor::White => out,
+            CellColor::Italics => format!("<i>{}</i>", out),
+            color => format!(
+                "<c.color{}>{}</c>",
+                format!("{:?}", color).to_uppercase(),
+                out
+            ),
+        };
+
+        out
+    }
+}
+
+struct StyledRun {
+    style: CellStyle,
+    text: String,
 }
 
 impl Default for CaptionFrame {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dtvcc_packet_len_zero_code_means_128_bytes() {
+        assert_eq!(Cea608ToTt::dtvcc_packet_len(0x00), 128);
+    }
+
+    #[test]
+    fn dtvcc_packet_len_nonzero_code_is_twice_the_code() {
+        assert_eq!(Cea608ToTt::dtvcc_packet_len(0x01), 2);
+        assert_eq!(Cea608ToTt::dtvcc_packet_len(0x3f), 126);
+    }
+
+    #[test]
+    fn dtvcc_packet_len_ignores_the_sequence_bits() {
+        // Bits 6-7 carry the sequence number, not part of the size code.
+        assert_eq!(Cea608ToTt::dtvcc_packet_len(0xc0), 128);
+        assert_eq!(Cea608ToTt::dtvcc_packet_len(0xc1), 2);
+    }
+
+    #[test]
+    fn decode_service_block_skips_c1_window_commands_without_emitting_them() {
+        let mut state = State::default();
+        // SPC (SetPenColor, 0x91) takes 3 parameter bytes; DFx (0x99) takes 6.
+        let block = [
+            b'h', b'i', 0x91, 0xff, 0xff, 0xff, 0x99, 0, 0, 0, 0, 0, 0, b'!',
+        ];
+        Cea608ToTt::decode_service_block(&mut state, &block);
+        assert_eq!(state.dtvcc_service_text, "hi!");
+    }
+
+    #[test]
+    fn decode_service_block_skips_ext1_and_plain_c0_codes() {
+        let mut state = State::default();
+        let block = [b'a', 0x10, 0x00, 0x05, b'b'];
+        Cea608ToTt::decode_service_block(&mut state, &block);
+        assert_eq!(state.dtvcc_service_text, "ab");
+    }
+
+    #[test]
+    fn decode_service_block_newline_on_cr() {
+        let mut state = State::default();
+        let block = [b'a', 0x0d, b'b'];
+        Cea608ToTt::decode_service_block(&mut state, &block);
+        assert_eq!(state.dtvcc_service_text, "a\nb");
+    }
+
+    #[test]
+    fn parse_dtvcc_packet_reads_extended_service_number() {
+        let mut state = State::default();
+        state.dtvcc_service_number = 9;
+
+        // service_header: service_number=7 (extended), block_size=2; followed by the
+        // extension byte (top 6 bits = 9) and the 2-byte block payload.
+        let data = [0x00, 0xe2, 0x24, b'h', b'i'];
+        Cea608ToTt::parse_dtvcc_packet(&mut state, &data);
+        assert_eq!(state.dtvcc_service_text, "hi");
+    }
+
+    #[test]
+    fn parse_dtvcc_packet_skips_extended_blocks_for_other_services() {
+        let mut state = State::default();
+        state.dtvcc_service_number = 1;
+
+        let data = [0x00, 0xe2, 0x24, b'h', b'i'];
+        Cea608ToTt::parse_dtvcc_packet(&mut state, &data);
+        assert_eq!(state.dtvcc_service_text, "");
+    }
+
+    #[test]
+    fn unwrap_s334_1a_tags_field_from_the_top_bit() {
+        let data = [0x80, 0x01, 0x02, 0x00, 0x03, 0x04];
+        assert_eq!(
+            Cea608ToTt::unwrap_s334_1a(&data),
+            vec![(0, 0x01, 0x02), (1, 0x03, 0x04)]
+        );
+    }
+
+    #[test]
+    fn unwrap_cdp_rejects_bad_header() {
+        let data = [0x00; 8];
+        assert!(Cea608ToTt::unwrap_cdp(&data).is_none());
+    }
+
+    #[test]
+    fn unwrap_cdp_rejects_short_packets() {
+        let data = [0x96, 0x69, 0, 0, 0, 0];
+        assert!(Cea608ToTt::unwrap_cdp(&data).is_none());
+    }
+
+    #[test]
+    fn unwrap_cdp_extracts_valid_608_pairs_and_skips_dtvcc() {
+        let mut data = vec![0x96, 0x69, 0, 0, 0, 0, 0];
+        data.push(0x72); // cc_data_id
+        data.push(0x80 | 0x02); // cc_count = 2
+                                 // cc_valid, cc_type=0 (field 1) pair
+        data.extend_from_slice(&[0xfc, 0x41, 0x42]);
+        // cc_valid, cc_type=3 (DTVCC_PACKET_START), must be skipped
+        data.extend_from_slice(&[0xff, 0x00, 0x00]);
+        assert_eq!(Cea608ToTt::unwrap_cdp(&data), Some(vec![(0, 0x41, 0x42)]));
+    }
+
+    #[test]
+    fn unwrap_cdp_skips_time_code_section_by_length_not_by_scanning() {
+        // cdp_flags (byte 4) with time_code_present (0x10) set.
+        let mut data = vec![0x96, 0x69, 0, 0, 0x10, 0, 0];
+        // time_code_section: 1 byte id + 4 byte payload, deliberately containing a byte
+        // that looks like the cc_data id so a naive scan would misparse it.
+        data.extend_from_slice(&[0x71, 0x72, 0x72, 0x72, 0x72]);
+        data.push(0x72); // cc_data_id
+        data.push(0x01); // cc_count = 1
+        data.extend_from_slice(&[0xfc, 0x41, 0x42]);
+        assert_eq!(Cea608ToTt::unwrap_cdp(&data), Some(vec![(0, 0x41, 0x42)]));
+    }
+
+    #[test]
+    fn is_roll_up_continuation_true_when_one_row_scrolled_in() {
+        let old: VecDeque<String> = vec!["a".to_string(), "b".to_string()].into();
+        assert!(Cea608ToTt::is_roll_up_continuation(
+            &old,
+            &["a", "b", "c"]
+        ));
+    }
+
+    #[test]
+    fn is_roll_up_continuation_false_on_unrelated_rows() {
+        let old: VecDeque<String> = vec!["a".to_string(), "b".to_string()].into();
+        assert!(!Cea608ToTt::is_roll_up_continuation(&old, &["x", "y"]));
+    }
+
+    #[test]
+    fn is_roll_up_continuation_false_when_more_than_one_row_new() {
+        let old: VecDeque<String> = vec!["a".to_string()].into();
+        assert!(!Cea608ToTt::is_roll_up_continuation(
+            &old,
+            &["a", "b", "c"]
+        ));
+    }
+}